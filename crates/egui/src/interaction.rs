@@ -1,8 +1,79 @@
 //! How mouse and touch interzcts with widgets.
 
+use std::collections::HashMap;
+
 use crate::*;
 
-use self::{hit_test::WidgetHits, input_state::PointerEvent, memory::InteractionState};
+use self::{
+    hit_test::WidgetHits,
+    input_state::PointerEvent,
+    memory::{DragPayload, InteractionState, LastClick},
+};
+
+/// What a widget wants to happen when it senses one or more touches pressing down on it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GrabMode {
+    /// The default: a single point of contact drags the widget.
+    Drag,
+
+    /// Aggregate two or more touches into a pan gesture, optionally also tracking scale and/or
+    /// rotation. See [`GestureSnapshot`].
+    Pan {
+        /// Track the ratio of this frame's mean touch distance from the centroid to last
+        /// frame's.
+        scale: bool,
+
+        /// Track the mean signed angular change of each touch around the centroid.
+        rotate: bool,
+    },
+}
+
+/// A multi-touch pan/zoom/rotate gesture, aggregated over all active touches on a widget whose
+/// [`GrabMode`] is [`GrabMode::Pan`].
+///
+/// `scale` and `rotation` are `1.0` and `0.0` respectively (i.e. "no change") whenever they are
+/// disabled by the widget's [`GrabMode`], or when only one touch is active. No gesture is
+/// reported at all on a frame where the reference frame is re-seeded.
+#[derive(Clone, Copy, Debug)]
+pub struct GestureSnapshot {
+    /// Change in the centroid of the active touches since last frame, in points.
+    pub translation: Vec2,
+
+    /// Ratio of this frame's mean touch distance from the centroid to last frame's.
+    pub scale: f32,
+
+    /// Mean signed angular change of the touches around the centroid, in radians.
+    pub rotation: f32,
+}
+
+/// The widget clicked this frame, and how many clicks in a row this is. See
+/// [`InteractionSnapshot::clicked`].
+#[derive(Clone, Copy, Debug)]
+pub struct ClickInfo {
+    pub widget: WidgetRect,
+
+    /// `1` for a plain click, `2` for a double-click, `3` for a triple-click, etc.
+    pub click_count: u32,
+}
+
+/// A click within this distance of the previous one, and within [`MAX_CLICK_INTERVAL`] of it,
+/// continues the same click streak (double-click, triple-click, ...) instead of starting a new
+/// one.
+const MAX_CLICK_DIST: f32 = 6.0;
+
+/// See [`MAX_CLICK_DIST`].
+const MAX_CLICK_INTERVAL: f64 = 0.3;
+
+/// The pointer position and elapsed time of an in-progress press, as reported by
+/// [`InteractionSnapshot::press_origin`] and [`InteractionSnapshot::press_duration`].
+#[derive(Clone, Copy, Debug)]
+pub struct PressState {
+    /// Where the pointer was when the press that is holding this widget down started.
+    pub press_origin: Pos2,
+
+    /// How long, in seconds, the press that is holding this widget down has lasted so far.
+    pub press_duration: f64,
+}
 
 /// Calculated at the start of each frame
 /// based on:
@@ -11,31 +82,143 @@ use self::{hit_test::WidgetHits, input_state::PointerEvent, memory::InteractionS
 /// * Current [`InteractionState`].
 #[derive(Clone, Default)]
 pub struct InteractionSnapshot {
-    /// The widget that got clicked this frame.
-    pub clicked: Option<WidgetRect>,
+    /// The widget that got clicked this frame, per pointer button, along with how many clicks in
+    /// a row this is (`1` for a plain click, `2` for a double-click, `3` for a triple-click, etc).
+    pub clicked: HashMap<PointerButton, ClickInfo>,
 
-    /// Drag started on this widget this frame.
+    /// Drag started on this widget this frame, per pointer button.
     ///
     /// This will also be found in `dragged` this frame.
-    pub drag_started: Option<WidgetRect>,
+    pub drag_started: HashMap<PointerButton, WidgetRect>,
 
-    /// This widget is being dragged this frame.
+    /// This widget is being dragged this frame, per pointer button.
     ///
     /// Set the same frame a drag starts,
     /// but unset the frame a drag ends.
-    pub dragged: Option<WidgetRect>,
+    pub dragged: HashMap<PointerButton, WidgetRect>,
 
-    /// This widget was let go this frame,
-    /// after having been dragged.
+    /// This widget was let go this frame, after having been dragged, per pointer button.
     ///
     /// The widget will not be found in [`Self::dragged`] this frame.
-    pub drag_ended: Option<WidgetRect>,
+    pub drag_ended: HashMap<PointerButton, WidgetRect>,
 
     pub hovered: IdMap<WidgetRect>,
     pub contains_pointer: IdMap<WidgetRect>,
+
+    /// Whatever the pointer is over right now, from raw position alone.
+    ///
+    /// Unlike [`Self::hovered`], this stays populated even while a button is held and
+    /// [`Self::hovered`] is pinned to the dragged widget, so drop targets can highlight and the
+    /// cursor can react to what's underneath a dragged widget. It is also recomputed immediately
+    /// on release, so there is no one-frame stale-cursor glitch while waiting for the next
+    /// `Moved` event.
+    pub pointer_over: Option<WidgetRect>,
+
+    /// Every widget under the pointer this frame, ordered front-to-back: the topmost widget (the
+    /// one a click would hit) comes first.
+    ///
+    /// Unlike [`Self::hovered`], this is the *full* stack, regardless of capture -- so a parent
+    /// can inspect what's behind a widget it captured the pointer for.
+    pub hover_stack: Vec<WidgetRect>,
+
+    /// For every widget that is currently pressed down by some pointer button, where that press
+    /// started and how long it has lasted.
+    ///
+    /// Lets a widget compute drag delta from the true press point rather than the accumulated
+    /// per-frame motion, implement "long-press" gestures on touch, and reject micro-drags below a
+    /// distance threshold.
+    pub press: IdMap<PressState>,
+
+    /// The typed payload of the drag-and-drop operation in progress for each pointer button, if
+    /// any.
+    ///
+    /// Populated for the whole duration of a drag, not just the frame it ends on, so a drag
+    /// preview can render the payload while the drag is still in flight. A widget looking for a
+    /// drop (rather than a preview) should check [`Self::drag_ended`] for the same button to
+    /// tell a genuine drop apart from an in-progress drag, then call [`Self::take_dnd_payload`]
+    /// to claim it.
+    pub drag_payload: HashMap<PointerButton, std::sync::Arc<dyn std::any::Any + Send + Sync>>,
+
+    /// This frame's multi-touch pan/zoom/rotate gesture, per widget that opted into
+    /// [`GrabMode::Pan`] and currently has one or more touches on it. With a single touch only
+    /// the translation is reported.
+    pub gestures: IdMap<GestureSnapshot>,
+
+    /// The widget, if any, that captured the pointer this frame. See [`Self::is_above_capture`].
+    ///
+    /// Cleared automatically the frame every pointer button comes back up, so a widget that
+    /// forgets to call `Response::release_pointer_capture` on release cannot block hover for
+    /// everything behind it beyond that.
+    pub pointer_capture: Option<Id>,
 }
 
 impl InteractionSnapshot {
+    /// The widget that was clicked this frame by the given button, if any.
+    pub fn clicked(&self, button: PointerButton) -> Option<WidgetRect> {
+        self.clicked.get(&button).map(|c| c.widget)
+    }
+
+    /// How many clicks in a row landed on the given button this frame (`0` if none did).
+    pub fn click_count(&self, button: PointerButton) -> u32 {
+        self.clicked.get(&button).map_or(0, |c| c.click_count)
+    }
+
+    /// Where the pointer was when the press currently holding `id` down started, if any.
+    pub fn press_origin(&self, id: Id) -> Option<Pos2> {
+        self.press.get(&id).map(|p| p.press_origin)
+    }
+
+    /// How long, in seconds, `id` has been held down for, if it is currently pressed.
+    pub fn press_duration(&self, id: Id) -> Option<f64> {
+        self.press.get(&id).map(|p| p.press_duration)
+    }
+
+    /// The widget that is being dragged this frame by the given button, if any.
+    pub fn dragged(&self, button: PointerButton) -> Option<WidgetRect> {
+        self.dragged.get(&button).copied()
+    }
+
+    /// The widget whose drag started this frame for the given button, if any.
+    pub fn drag_started(&self, button: PointerButton) -> Option<WidgetRect> {
+        self.drag_started.get(&button).copied()
+    }
+
+    /// The widget whose drag ended this frame for the given button, if any.
+    pub fn drag_ended(&self, button: PointerButton) -> Option<WidgetRect> {
+        self.drag_ended.get(&button).copied()
+    }
+
+    /// Take the drag-and-drop payload for `button`, if it holds a value of type `T`.
+    ///
+    /// Only one widget should claim the payload per frame; calling this does not remove the
+    /// payload from the snapshot, so a widget should check `drag_ended` for the same button is
+    /// also the widget it cares about before trusting the result.
+    pub fn take_dnd_payload<T: std::any::Any + Send + Sync>(
+        &self,
+        button: PointerButton,
+    ) -> Option<std::sync::Arc<T>> {
+        self.drag_payload.get(&button)?.clone().downcast::<T>().ok()
+    }
+
+    /// This frame's pan/zoom/rotate gesture for `id`, if it opted into [`GrabMode::Pan`] and has
+    /// one or more active touches (translation only, with a single touch).
+    pub fn gesture(&self, id: Id) -> Option<GestureSnapshot> {
+        self.gestures.get(&id).copied()
+    }
+
+    /// Is `id` at or above the widget that captured the pointer this frame (if any) in the hover
+    /// stack? Widgets strictly behind a capture still show up in [`Self::contains_pointer`], but
+    /// should not react as if they were hovered.
+    pub fn is_above_capture(&self, id: Id) -> bool {
+        let Some(captured_at) = self
+            .pointer_capture
+            .and_then(|captured_id| self.hover_stack.iter().position(|w| w.id == captured_id))
+        else {
+            return true;
+        };
+        self.hover_stack[..=captured_at].iter().any(|w| w.id == id)
+    }
+
     pub fn ui(&self, ui: &mut crate::Ui) {
         let Self {
             clicked,
@@ -44,6 +227,12 @@ impl InteractionSnapshot {
             drag_ended,
             hovered,
             contains_pointer,
+            pointer_over: _,
+            press: _,
+            drag_payload: _,
+            gestures: _,
+            hover_stack: _,
+            pointer_capture: _,
         } = self;
 
         fn wr_ui<'a>(ui: &mut crate::Ui, widgets: impl IntoIterator<Item = &'a WidgetRect>) {
@@ -54,19 +243,19 @@ impl InteractionSnapshot {
 
         crate::Grid::new("interaction").show(ui, |ui| {
             ui.label("clicked");
-            wr_ui(ui, clicked);
+            wr_ui(ui, clicked.values().map(|c| &c.widget));
             ui.end_row();
 
             ui.label("drag_started");
-            wr_ui(ui, drag_started);
+            wr_ui(ui, drag_started.values());
             ui.end_row();
 
             ui.label("dragged");
-            wr_ui(ui, dragged);
+            wr_ui(ui, dragged.values());
             ui.end_row();
 
             ui.label("drag_ended");
-            wr_ui(ui, drag_ended);
+            wr_ui(ui, drag_ended.values());
             ui.end_row();
 
             ui.label("hovered");
@@ -89,83 +278,263 @@ pub(crate) fn interact(
 ) -> InteractionSnapshot {
     crate::profile_function!();
 
-    if let Some(id) = interaction.click_id {
-        if !widgets.by_id.contains_key(&id) {
-            // The widget we were interested in clicking is gone.
-            interaction.click_id = None;
-        }
-    }
-    if let Some(id) = interaction.drag_id {
+    // Forget about any widget we were interested in clicking that is no longer with us.
+    interaction
+        .click_id
+        .retain(|_button, id| widgets.by_id.contains_key(id));
+
+    // Same for widgets that opted into multi-touch gesture tracking but are now gone for good.
+    interaction.grab_mode.retain(|id, _| widgets.by_id.contains_key(id));
+    let grab_mode = &interaction.grab_mode;
+    interaction
+        .gesture_touches
+        .retain(|id, _| grab_mode.contains_key(id));
+
+    // Note: we deliberately do NOT do the same for `drag_id`.
+    // The widget we were interested in dragging may be gone.
+    // This is fine! This could be drag-and-drop,
+    // and the widget being dragged is now "in the air" and thus
+    // not registered in the new frame.
+
+    if let Some(id) = interaction.pointer_capture {
         if !widgets.by_id.contains_key(&id) {
-            // The widget we were interested in dragging is gone.
-            // This is fine! This could be drag-and-drop,
-            // and the widget being dragged is now "in the air" and thus
-            // not registered in the new frame.
+            // The widget that captured the pointer is gone; stop capturing.
+            interaction.pointer_capture = None;
         }
     }
 
-    let mut clicked = None;
+    let mut clicked: HashMap<PointerButton, ClickInfo> = HashMap::default();
 
     // Note: in the current code a press-release in the same frame is NOT considered a drag.
     for pointer_event in &input.pointer.pointer_events {
         match pointer_event {
             PointerEvent::Moved(_) => {}
 
-            PointerEvent::Pressed { .. } => {
+            PointerEvent::Pressed { pos, button } => {
                 // Maybe new click?
-                if interaction.click_id.is_none() {
-                    interaction.click_id = hits.click.map(|w| w.id);
+                if !interaction.click_id.contains_key(button) {
+                    if let Some(hit) = hits.click {
+                        interaction.click_id.insert(*button, hit.id);
+                    }
                 }
 
                 // Maybe new drag?
-                if interaction.drag_id.is_none() {
-                    interaction.drag_id = hits.drag.map(|w| w.id);
+                if !interaction.drag_id.contains_key(button) {
+                    if let Some(hit) = hits.drag {
+                        interaction.drag_id.insert(*button, hit.id);
+                    }
                 }
+
+                // Remember where and when this button went down, for the lifetime of the press.
+                interaction.press_origin.insert(*button, *pos);
+                interaction.press_start_time.insert(*button, input.time);
             }
 
-            PointerEvent::Released { click, button: _ } => {
-                if click.is_some() {
-                    if let Some(widget) = interaction.click_id.and_then(|id| widgets.by_id.get(&id))
+            PointerEvent::Released { click, button } => {
+                if let Some(click) = click {
+                    if let Some(widget) = interaction
+                        .click_id
+                        .get(button)
+                        .and_then(|id| widgets.by_id.get(id))
                     {
-                        clicked = Some(*widget);
+                        let click_count = click_streak_count(
+                            interaction.last_click.get(button),
+                            widget.id,
+                            click.pos,
+                            input.time,
+                        );
+
+                        interaction.last_click.insert(
+                            *button,
+                            LastClick {
+                                id: widget.id,
+                                pos: click.pos,
+                                time: input.time,
+                                count: click_count,
+                            },
+                        );
+
+                        clicked.insert(
+                            *button,
+                            ClickInfo {
+                                widget: *widget,
+                                click_count,
+                            },
+                        );
                     }
                 }
 
-                interaction.drag_id = None;
-                interaction.click_id = None;
+                interaction.drag_id.remove(button);
+                interaction.click_id.remove(button);
+                interaction.press_origin.remove(button);
+                interaction.press_start_time.remove(button);
             }
         }
     }
 
-    // Check if we're dragging something:
-    let mut dragged = None;
-    if let Some(widget) = interaction.drag_id.and_then(|id| widgets.by_id.get(&id)) {
-        let is_dragged = if widget.sense.click && widget.sense.drag {
-            // This widget is sensitive to both clicks and drags.
-            // When the mouse first is pressed, it could be either,
-            // so we postpone the decision until we know.
-            input.pointer.is_decidedly_dragging()
-        } else {
-            // This widget is just sensitive to drags, so we can mark it as dragged right away:
-            widget.sense.drag
-        };
+    release_capture_if_nothing_pressed(interaction);
+
+    // Check if we're dragging something, per button:
+    let mut dragged: HashMap<PointerButton, WidgetRect> = HashMap::default();
+    for (&button, id) in &interaction.drag_id {
+        if let Some(widget) = widgets.by_id.get(id) {
+            let is_dragged = if widget.sense.click && widget.sense.drag {
+                // This widget is sensitive to both clicks and drags.
+                // When the mouse first is pressed, it could be either,
+                // so we postpone the decision until we know.
+                input.pointer.is_decidedly_dragging()
+            } else {
+                // This widget is just sensitive to drags, so we can mark it as dragged right away:
+                widget.sense.drag
+            };
+
+            if is_dragged {
+                dragged.insert(button, *widget);
+            }
+        }
+    }
+
+    // A drag starts or ends, per button, whenever this frame's `dragged` entry for that button
+    // differs from last frame's.
+    let mut drag_started: HashMap<PointerButton, WidgetRect> = HashMap::default();
+    let mut drag_ended: HashMap<PointerButton, WidgetRect> = HashMap::default();
+    let changed_buttons: std::collections::HashSet<PointerButton> = prev_snapshot
+        .dragged
+        .keys()
+        .chain(dragged.keys())
+        .copied()
+        .collect();
+    for button in changed_buttons {
+        let was = prev_snapshot.dragged.get(&button).copied();
+        let now = dragged.get(&button).copied();
+        if was != now {
+            if let Some(now) = now {
+                drag_started.insert(button, now);
+            }
+            if let Some(was) = was {
+                drag_ended.insert(button, was);
+            }
+        }
+    }
+
+    // The drag-and-drop payload is tracked per button, like `click_id`/`drag_id`: whichever
+    // drag set it owns it until that same button's drag ends, so two drags on different
+    // buttons in flight at once don't clobber each other's payload.
+    let drag_payload = collect_drag_payloads(
+        &mut interaction.active_drag,
+        drag_ended.keys().copied(),
+        |button| dragged.contains_key(button),
+    );
+
+    // For every widget currently held down by some button, report where and when that press
+    // started. A widget can be the target of both a click-candidate and a drag-candidate at
+    // once (e.g. before `is_decidedly_dragging` resolves), so we visit both maps; if they name
+    // different ids, both are recorded. If the same id is held by more than one button, the
+    // last write wins.
+    let mut press: IdMap<PressState> = IdMap::default();
+    for (button, id) in interaction.click_id.iter().chain(&interaction.drag_id) {
+        if let Some(&press_origin) = interaction.press_origin.get(button) {
+            let start_time = interaction
+                .press_start_time
+                .get(button)
+                .copied()
+                .unwrap_or(input.time);
+            press.insert(
+                *id,
+                PressState {
+                    press_origin,
+                    press_duration: input.time - start_time,
+                },
+            );
+        }
+    }
 
-        if is_dragged {
-            dragged = Some(*widget);
+    // Keep our persistent view of "every touch currently on the screen" up to date.
+    for event in &input.events {
+        if let Event::Touch { id, phase, pos, .. } = event {
+            match phase {
+                TouchPhase::Start | TouchPhase::Move => {
+                    interaction.active_touches.insert(*id, *pos);
+                }
+                TouchPhase::End | TouchPhase::Cancel => {
+                    interaction.active_touches.remove(id);
+                }
+            }
         }
     }
 
-    let drag_changed = dragged != prev_snapshot.dragged;
-    let drag_ended = drag_changed.then_some(prev_snapshot.dragged).flatten();
-    let drag_started = drag_changed.then_some(dragged).flatten();
+    // Aggregate multi-touch gestures for every widget that asked for `GrabMode::Pan`.
+    let mut gestures: IdMap<GestureSnapshot> = IdMap::default();
+    for (&id, &grab_mode) in &interaction.grab_mode {
+        let GrabMode::Pan { scale, rotate } = grab_mode else {
+            interaction.gesture_touches.remove(&id);
+            continue;
+        };
+
+        let Some(widget) = widgets.by_id.get(&id) else {
+            interaction.gesture_touches.remove(&id);
+            continue;
+        };
+
+        let current_touches: HashMap<TouchId, Pos2> = interaction
+            .active_touches
+            .iter()
+            .filter(|(_, &pos)| widget.interact_rect.contains(pos))
+            .map(|(&id, &pos)| (id, pos))
+            .collect();
+
+        if current_touches.len() == 1 {
+            // Down to a single touch (e.g. a pinch that let go of one finger): keep reporting a
+            // translation-only gesture against that touch's previous position, rather than
+            // dropping the gesture the instant a second finger lifts. `scale`/`rotation` need at
+            // least two touches, so they are left at their "no change" defaults.
+            if let Some(prev_touches) = interaction.gesture_touches.get(&id) {
+                let (&touch_id, &curr_pos) = current_touches.iter().next().expect("len == 1");
+                if let Some(&prev_pos) = prev_touches.get(&touch_id) {
+                    gestures.insert(
+                        id,
+                        GestureSnapshot {
+                            translation: curr_pos - prev_pos,
+                            scale: 1.0,
+                            rotation: 0.0,
+                        },
+                    );
+                }
+            }
+
+            interaction.gesture_touches.insert(id, current_touches);
+            continue;
+        }
+
+        if current_touches.is_empty() {
+            // No touches left at all; forget the reference frame so a future touch re-seeds
+            // cleanly instead of producing a spurious jump.
+            interaction.gesture_touches.remove(&id);
+            continue;
+        }
+
+        let prev_touches = interaction.gesture_touches.get(&id);
 
-    // if let Some(drag_started) = drag_started {
-    //     eprintln!(
-    //         "Started dragging {} {:?}",
-    //         drag_started.id.short_debug_format(),
-    //         drag_started.rect
-    //     );
-    // }
+        let same_touch_set = prev_touches.is_some_and(|prev| {
+            let prev_ids: std::collections::HashSet<_> = prev.keys().collect();
+            let curr_ids: std::collections::HashSet<_> = current_touches.keys().collect();
+            prev_ids == curr_ids
+        });
+
+        if same_touch_set {
+            if let Some(prev_touches) = prev_touches {
+                gestures.insert(
+                    id,
+                    compute_gesture_delta(prev_touches, &current_touches, scale, rotate),
+                );
+            }
+        }
+        // else: the set of touches changed since last frame (or there is no last frame) --
+        // re-seed the reference frame below
+        // rather than emitting a spurious jump.
+
+        interaction.gesture_touches.insert(id, current_touches);
+    }
 
     let contains_pointer: IdMap<WidgetRect> = hits
         .contains_pointer
@@ -175,9 +544,24 @@ pub(crate) fn interact(
         .map(|w| (w.id, *w))
         .collect();
 
-    let hovered = if clicked.is_some() || dragged.is_some() {
+    // `hits.contains_pointer` is ordered back-to-front (topmost last); expose it the other way
+    // around so `hover_stack[0]` is always the topmost widget.
+    let hover_stack: Vec<WidgetRect> = hits.contains_pointer.iter().rev().copied().collect();
+
+    // Unlike `hovered` below, this always reflects the raw hit-test for the current pointer
+    // position, even while a drag pins `hovered` to the dragged widget. `hits` is recomputed
+    // fresh every frame from the pointer's current position, so this is also immediately
+    // correct the very frame a drag ends, without waiting for the next `Moved` event.
+    let pointer_over = hover_stack.first().copied();
+
+    let hovered = if !clicked.is_empty() || !dragged.is_empty() {
         // If currently clicking or dragging, nothing else is hovered.
-        clicked.iter().chain(&dragged).map(|w| (w.id, *w)).collect()
+        clicked
+            .values()
+            .map(|c| c.widget)
+            .chain(dragged.values().copied())
+            .map(|w| (w.id, w))
+            .collect()
     } else if hits.click.is_some() || hits.drag.is_some() {
         // We are hovering over an interactive widget or two.
         hits.click
@@ -186,13 +570,16 @@ pub(crate) fn interact(
             .map(|w| (w.id, *w))
             .collect()
     } else {
-        // Whatever is topmost is what we are hovering.
-        // TODO: consider handle hovering over multiple top-most widgets?
-        // TODO: allow hovering close widgets?
-        hits.contains_pointer
-            .last()
+        // By default only the topmost widget is hovered. If some widget further down the stack
+        // explicitly captured the pointer (e.g. because the widgets above it are transparent
+        // catch-all overlays that declined to), hover extends down to and including it instead.
+        let captured_at = interaction
+            .pointer_capture
+            .and_then(|id| hover_stack.iter().position(|w| w.id == id));
+        let visible_end = captured_at.map_or(usize::from(!hover_stack.is_empty()), |i| i + 1);
+        hover_stack[..visible_end]
+            .iter()
             .map(|w| (w.id, *w))
-            .into_iter()
             .collect()
     };
 
@@ -203,5 +590,321 @@ pub(crate) fn interact(
         drag_ended,
         contains_pointer,
         hovered,
+        hover_stack,
+        pointer_over,
+        press,
+        drag_payload,
+        gestures,
+        pointer_capture: interaction.pointer_capture,
+    }
+}
+
+/// Aggregate a set of at-least-two touches, identified by [`TouchId`] in both maps, into a single
+/// translation/scale/rotation delta relative to their previous positions.
+fn compute_gesture_delta(
+    prev: &HashMap<TouchId, Pos2>,
+    curr: &HashMap<TouchId, Pos2>,
+    track_scale: bool,
+    track_rotate: bool,
+) -> GestureSnapshot {
+    let centroid = |points: &HashMap<TouchId, Pos2>| -> Pos2 {
+        let sum: Vec2 = points.values().map(|p| p.to_vec2()).sum();
+        (sum / (points.len() as f32)).to_pos2()
+    };
+
+    let prev_centroid = centroid(prev);
+    let curr_centroid = centroid(curr);
+    let translation = curr_centroid - prev_centroid;
+
+    let mut scale = 1.0;
+    if track_scale {
+        let mean_dist = |points: &HashMap<TouchId, Pos2>, centroid: Pos2| -> f32 {
+            points.values().map(|p| p.distance(centroid)).sum::<f32>() / points.len() as f32
+        };
+        let prev_dist = mean_dist(prev, prev_centroid);
+        let curr_dist = mean_dist(curr, curr_centroid);
+        if prev_dist > 1e-5 {
+            scale = curr_dist / prev_dist;
+        }
+    }
+
+    let mut rotation = 0.0;
+    if track_rotate {
+        let mut angle_sum = 0.0;
+        let mut count = 0;
+        for (id, &curr_pos) in curr {
+            if let Some(&prev_pos) = prev.get(id) {
+                let prev_angle = (prev_pos - prev_centroid).angle();
+                let curr_angle = (curr_pos - curr_centroid).angle();
+                // `angle()` returns a value in `(-PI, PI]`, so a touch whose angle crosses the
+                // +-PI boundary between frames would otherwise show up as a spurious ~2*PI jump
+                // instead of the true (small) delta. Wrap back into `[-PI, PI]`.
+                let delta = (curr_angle - prev_angle + std::f32::consts::PI)
+                    .rem_euclid(2.0 * std::f32::consts::PI)
+                    - std::f32::consts::PI;
+                angle_sum += delta;
+                count += 1;
+            }
+        }
+        if count > 0 {
+            rotation = angle_sum / count as f32;
+        }
+    }
+
+    GestureSnapshot {
+        translation,
+        scale,
+        rotation,
+    }
+}
+
+/// Release the pointer capture once no pointer button is down any more, so a widget that forgets
+/// to call `release_pointer_capture` on release cannot permanently block hover for everything
+/// behind it.
+///
+/// Extracted out of [`interact`] so this auto-release rule is directly testable without needing
+/// a full frame's [`WidgetHits`]/[`InputState`].
+fn release_capture_if_nothing_pressed(interaction: &mut InteractionState) {
+    if interaction.press_origin.is_empty() {
+        interaction.pointer_capture = None;
+    }
+}
+
+/// Hand each ended button's drag-and-drop payload off into this frame's snapshot, then forget
+/// it; carry forward a clone of the payload for every button still dragging, and drop the
+/// reference entirely for any button that isn't dragging any more.
+///
+/// Extracted out of [`interact`] (and expressed only in terms of `PointerButton`, not
+/// `WidgetRect`) so the multi-button behavior -- two independent drags in flight at once don't
+/// clobber each other's payload -- is directly testable without needing a full frame's
+/// [`WidgetHits`]/[`InputState`].
+fn collect_drag_payloads(
+    active_drag: &mut HashMap<PointerButton, DragPayload>,
+    ended_buttons: impl Iterator<Item = PointerButton>,
+    still_dragging: impl Fn(&PointerButton) -> bool,
+) -> HashMap<PointerButton, std::sync::Arc<dyn std::any::Any + Send + Sync>> {
+    let mut drag_payload = HashMap::default();
+    for button in ended_buttons {
+        if let Some(drag) = active_drag.remove(&button) {
+            drag_payload.insert(button, drag.payload);
+        }
+    }
+    for (&button, drag) in active_drag.iter() {
+        drag_payload.insert(button, drag.payload.clone());
+    }
+    active_drag.retain(|button, _| still_dragging(button));
+    drag_payload
+}
+
+/// How many clicks in a row a new click on `id` at `pos`/`time` continues, given the previous
+/// click of the same button (if any).
+///
+/// A click continues the previous streak (double-click, triple-click, ...) only if it lands on
+/// the same widget, close enough in both position and time; anything else -- a different widget,
+/// or too far, or too slow -- resets the streak to `1`.
+fn click_streak_count(last: Option<&LastClick>, id: Id, pos: Pos2, time: f64) -> u32 {
+    match last {
+        Some(last)
+            if last.id == id
+                && last.pos.distance(pos) <= MAX_CLICK_DIST
+                && time - last.time <= MAX_CLICK_INTERVAL =>
+        {
+            last.count + 1
+        }
+        _ => 1,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn gesture_delta_rotation_wraps_across_the_pi_boundary() {
+        // Two touches rotating slightly further past +-PI should report a small delta, not a
+        // spurious jump of nearly 2*PI.
+        let a = pos2(0.0, 0.0);
+        let prev = HashMap::from([
+            (TouchId::from(0), a + Vec2::angled(std::f32::consts::PI - 0.05)),
+            (TouchId::from(1), a - Vec2::angled(std::f32::consts::PI - 0.05)),
+        ]);
+        let curr = HashMap::from([
+            (
+                TouchId::from(0),
+                a + Vec2::angled(-std::f32::consts::PI + 0.05),
+            ),
+            (
+                TouchId::from(1),
+                a - Vec2::angled(-std::f32::consts::PI + 0.05),
+            ),
+        ]);
+
+        let gesture = compute_gesture_delta(&prev, &curr, false, true);
+
+        assert!(
+            gesture.rotation.abs() < 0.5,
+            "expected a small rotation delta, got {}",
+            gesture.rotation
+        );
+    }
+
+    #[test]
+    fn gesture_delta_scale_and_rotation_disabled_are_left_at_defaults() {
+        let prev = HashMap::from([
+            (TouchId::from(0), pos2(0.0, 0.0)),
+            (TouchId::from(1), pos2(10.0, 0.0)),
+        ]);
+        let curr = HashMap::from([
+            (TouchId::from(0), pos2(0.0, 0.0)),
+            (TouchId::from(1), pos2(20.0, 0.0)),
+        ]);
+
+        let gesture = compute_gesture_delta(&prev, &curr, false, false);
+
+        assert_eq!(gesture.scale, 1.0);
+        assert_eq!(gesture.rotation, 0.0);
+    }
+
+    #[test]
+    fn click_streak_continues_on_same_widget_within_distance_and_time() {
+        let id = Id::new("widget");
+        let last = LastClick {
+            id,
+            pos: pos2(10.0, 10.0),
+            time: 1.0,
+            count: 1,
+        };
+
+        let count = click_streak_count(Some(&last), id, pos2(12.0, 10.0), 1.1);
+
+        assert_eq!(count, 2);
+    }
+
+    #[test]
+    fn click_streak_resets_on_different_widget() {
+        let last = LastClick {
+            id: Id::new("widget_a"),
+            pos: pos2(10.0, 10.0),
+            time: 1.0,
+            count: 2,
+        };
+
+        let count = click_streak_count(Some(&last), Id::new("widget_b"), pos2(10.0, 10.0), 1.1);
+
+        assert_eq!(count, 1);
+    }
+
+    #[test]
+    fn different_pointer_buttons_do_not_share_a_click_streak() {
+        // Regression test: a primary click immediately followed by a secondary click on the same
+        // widget must not be counted as a 2-click streak -- each button tracks its own streak.
+        let mut last_click: HashMap<PointerButton, LastClick> = HashMap::default();
+        let id = Id::new("widget");
+        let pos = pos2(10.0, 10.0);
+
+        let primary_count =
+            click_streak_count(last_click.get(&PointerButton::Primary), id, pos, 1.0);
+        last_click.insert(
+            PointerButton::Primary,
+            LastClick {
+                id,
+                pos,
+                time: 1.0,
+                count: primary_count,
+            },
+        );
+
+        let secondary_count =
+            click_streak_count(last_click.get(&PointerButton::Secondary), id, pos, 1.05);
+
+        assert_eq!(primary_count, 1);
+        assert_eq!(secondary_count, 1);
+    }
+
+    fn drag_payload_str(payload: &str) -> DragPayload {
+        DragPayload {
+            payload: std::sync::Arc::new(payload.to_owned()),
+            cursor_offset: Vec2::ZERO,
+        }
+    }
+
+    #[test]
+    fn independent_drags_on_different_buttons_do_not_clobber_each_others_payload() {
+        // Regression test: a middle-button drag starting (and ending) while a left-button drag
+        // is still in flight must not lose the left-button drag's payload.
+        let mut active_drag = HashMap::from([
+            (PointerButton::Primary, drag_payload_str("A")),
+            (PointerButton::Middle, drag_payload_str("B")),
+        ]);
+        let ended_buttons = [PointerButton::Middle];
+        let still_dragging = std::collections::HashSet::from([PointerButton::Primary]);
+
+        let drag_payload = collect_drag_payloads(
+            &mut active_drag,
+            ended_buttons.into_iter(),
+            |button| still_dragging.contains(button),
+        );
+
+        assert_eq!(
+            drag_payload
+                .get(&PointerButton::Middle)
+                .and_then(|p| p.downcast_ref::<String>())
+                .map(String::as_str),
+            Some("B"),
+            "the button that ended this frame should hand off its own payload"
+        );
+        assert_eq!(
+            drag_payload
+                .get(&PointerButton::Primary)
+                .and_then(|p| p.downcast_ref::<String>())
+                .map(String::as_str),
+            Some("A"),
+            "the still-dragging button's payload must survive the other button's drag ending"
+        );
+        assert!(
+            active_drag.contains_key(&PointerButton::Primary),
+            "the still-dragging button should keep its active_drag entry"
+        );
+        assert!(
+            !active_drag.contains_key(&PointerButton::Middle),
+            "the ended button's active_drag entry should be gone"
+        );
+    }
+
+    #[test]
+    fn drag_payload_is_dropped_once_its_button_stops_dragging() {
+        let mut active_drag = HashMap::from([(PointerButton::Primary, drag_payload_str("A"))]);
+
+        // The button neither ended a drag this frame nor is still dragging -- e.g. a payload was
+        // set for a press that was released before it ever became a drag.
+        let drag_payload = collect_drag_payloads(&mut active_drag, std::iter::empty(), |_| false);
+
+        assert!(drag_payload.is_empty());
+        assert!(active_drag.is_empty());
+    }
+
+    #[test]
+    fn pointer_capture_is_released_once_nothing_is_pressed() {
+        let mut interaction = InteractionState {
+            pointer_capture: Some(Id::new("overlay")),
+            ..Default::default()
+        };
+
+        release_capture_if_nothing_pressed(&mut interaction);
+
+        assert_eq!(interaction.pointer_capture, None);
+    }
+
+    #[test]
+    fn pointer_capture_survives_while_a_button_is_still_down() {
+        let mut interaction = InteractionState {
+            pointer_capture: Some(Id::new("overlay")),
+            press_origin: HashMap::from([(PointerButton::Primary, pos2(0.0, 0.0))]),
+            ..Default::default()
+        };
+
+        release_capture_if_nothing_pressed(&mut interaction);
+
+        assert_eq!(interaction.pointer_capture, Some(Id::new("overlay")));
     }
 }
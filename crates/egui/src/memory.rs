@@ -0,0 +1,161 @@
+//! State that is carried from one frame to the next, used by [`crate::interaction::interact`]
+//! to resolve clicks and drags across frame boundaries.
+
+use std::any::Any;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use crate::{GrabMode, Id, IdMap, Pos2, PointerButton, TouchId, Vec2};
+
+/// A typed value being drag-and-dropped, set by the widget that started the drag (via
+/// [`InteractionState::set_drag_payload`]) and read by whatever the pointer drops it on.
+///
+/// Stored as `Arc<dyn Any>` rather than `Box<dyn Any>` so that [`InteractionState`] can stay
+/// [`Clone`].
+#[derive(Clone, Debug)]
+pub(crate) struct DragPayload {
+    pub payload: Arc<dyn Any + Send + Sync>,
+
+    /// Offset from the pointer to the dragged widget's origin, so a drag preview can be rendered
+    /// centered on the widget instead of snapping to the pointer.
+    pub cursor_offset: Vec2,
+}
+
+/// Mouse/touch interaction state that persists between frames.
+///
+/// A click or drag is not known for certain the instant the pointer goes down: we only find out
+/// once it is released (for a click) or once it has moved far enough (for a drag). In the
+/// meantime we need to remember which widget is the *candidate* for each held pointer button.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct InteractionState {
+    /// The widget that may end up clicked, per pointer button that is currently down.
+    pub click_id: HashMap<PointerButton, Id>,
+
+    /// The widget that may end up dragged, per pointer button that is currently down.
+    ///
+    /// Note: unlike `click_id`, a `drag_id` entry is allowed to point to a widget that is no
+    /// longer present in this frame's `widgets.by_id` -- that's just drag-and-drop, where the
+    /// dragged widget is "in the air" and temporarily absent from its usual place in the tree.
+    pub drag_id: HashMap<PointerButton, Id>,
+
+    /// Where the pointer was when each currently-down button was pressed, in screen coordinates.
+    ///
+    /// Kept around for the lifetime of the press (not just while a drag is active) so widgets can
+    /// measure drag delta from the true press point, implement long-press gestures, and reject
+    /// micro-drags below a distance threshold.
+    pub press_origin: HashMap<PointerButton, Pos2>,
+
+    /// `InputState::time` when each currently-down button was pressed.
+    pub press_start_time: HashMap<PointerButton, f64>,
+
+    /// The payload of the drag-and-drop operation currently in flight, per pointer button.
+    ///
+    /// Keyed by button (like `click_id`/`drag_id`/`press_origin`) so that independent drags on
+    /// different buttons don't clobber each other's payload. Set by the source widget once it
+    /// senses a drag starting, and cleared by [`crate::interaction::interact`] once that button
+    /// is no longer being dragged.
+    pub active_drag: HashMap<PointerButton, DragPayload>,
+
+    /// The grab mode a widget has opted into, set by the widget itself (e.g. via
+    /// `Response::set_grab_mode`). Absent means the default: a plain single-pointer drag.
+    pub grab_mode: IdMap<GrabMode>,
+
+    /// The touch positions seen last frame for each widget that is currently the target of a
+    /// [`GrabMode::Pan`] gesture, keyed by [`TouchId`].
+    ///
+    /// This is the reference frame the next frame's gesture delta is computed against; it is
+    /// re-seeded (rather than diffed) whenever the set of two or more active touches changes, so
+    /// that adding or removing a finger never produces a spurious jump. When a single touch
+    /// remains, that touch is diffed against its own position from last frame.
+    pub gesture_touches: IdMap<HashMap<TouchId, Pos2>>,
+
+    /// All touches currently in contact with the screen, updated incrementally frame-to-frame
+    /// from `Event::Touch`. Unlike [`crate::InputState::events`], which only carries this frame's
+    /// touch *deltas*, this is the full current set.
+    pub active_touches: HashMap<TouchId, Pos2>,
+
+    /// The widget, if any, that has asked to capture the pointer this frame and stop it from
+    /// propagating to whatever is behind it in the hover stack.
+    ///
+    /// Set by the widget itself (e.g. via `Response::capture_pointer`) so that overlays and
+    /// transparent catch-all regions can coexist with widgets behind them, and a parent can
+    /// decide whether a child's press should bubble. Cleared either explicitly via
+    /// `Response::release_pointer_capture`, or automatically by
+    /// [`crate::interaction::interact`] once no pointer button is down any more, so a widget
+    /// that forgets to release it does not permanently block hover for everything behind it.
+    pub pointer_capture: Option<Id>,
+
+    /// The most recent click for each pointer button: which widget it landed on, where, when,
+    /// and how many in a row.
+    ///
+    /// Keyed by button (like `click_id`, `drag_id`, `press_origin`, ...) so that e.g. a primary
+    /// click immediately followed by a secondary click on the same widget does not get counted
+    /// as a double-click streak.
+    ///
+    /// Compared against the next click of the same button to detect double- and triple-clicks: a
+    /// click on the same widget, close enough in both position and time to the last one of that
+    /// button, increments [`LastClick::count`] instead of resetting it to `1`.
+    pub last_click: HashMap<PointerButton, LastClick>,
+}
+
+/// See [`InteractionState::last_click`].
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct LastClick {
+    pub id: Id,
+    pub pos: Pos2,
+    pub time: f64,
+    pub count: u32,
+}
+
+impl InteractionState {
+    /// Attach a typed payload to the drag on `button` that is currently in progress, so that
+    /// whatever the pointer is released over can inspect it.
+    ///
+    /// `cursor_offset` is the offset from the pointer to the dragged widget's origin, used to
+    /// render a drag preview centered on the widget rather than snapped to the pointer.
+    pub fn set_drag_payload<T: Any + Send + Sync>(
+        &mut self,
+        button: PointerButton,
+        payload: T,
+        cursor_offset: Vec2,
+    ) {
+        self.active_drag.insert(
+            button,
+            DragPayload {
+                payload: Arc::new(payload),
+                cursor_offset,
+            },
+        );
+    }
+
+    /// The cursor offset of the payload currently being dragged by `button`, if any.
+    pub fn drag_payload_cursor_offset(&self, button: PointerButton) -> Option<Vec2> {
+        self.active_drag.get(&button).map(|drag| drag.cursor_offset)
+    }
+
+    /// Request that multi-touch gestures over `id` be aggregated into a [`crate::interaction::GestureSnapshot`]
+    /// instead of (or in addition to) being treated as a plain drag.
+    pub fn set_grab_mode(&mut self, id: Id, mode: GrabMode) {
+        self.grab_mode.insert(id, mode);
+    }
+
+    /// Mark `id` as having captured the pointer, until explicitly released or every pointer
+    /// button comes back up (see [`Self::pointer_capture`]).
+    ///
+    /// [`crate::interaction::interact`] consults the capture only for `hovered`, and only when
+    /// nothing is being clicked or dragged and the pointer is over no interactive widget: hover
+    /// then reaches from the topmost widget down to and including `id`, and no further. Clicks
+    /// and drags are resolved without regard to the capture.
+    pub fn capture_pointer(&mut self, id: Id) {
+        self.pointer_capture = Some(id);
+    }
+
+    /// Undo [`Self::capture_pointer`], letting the pointer interaction reach widgets behind the
+    /// previously-capturing widget again.
+    ///
+    /// Only needed to release a capture early; it is also released automatically once no
+    /// pointer button is down any more.
+    pub fn release_pointer_capture(&mut self) {
+        self.pointer_capture = None;
+    }
+}